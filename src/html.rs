@@ -0,0 +1,159 @@
+//! The HTML output target, built on the `transform` rewrite-rule
+//! mechanism: element names become tags, except for a few names with
+//! their own rules (`+` splice, empty or trailing `=` verbatim, literal
+//! `\`) so the renderer doesn't need a hardcoded special case for each
+//! of them.
+use std::io;
+use parser::{Blob, Elem, Node, Span, is_literal};
+use parser::delimiter::Delim;
+use transform::{Rule, Ruleset, transform};
+
+fn literal_bytes(bytes: Vec<u8>) -> Node<Blob> {
+    Node::Elem(Elem {
+        name: Blob::from(&b"\\"[..]),
+        delim: Delim::Parenthesis,
+        children: vec![Node::Text(Blob::from(bytes), Span::default())],
+        span: Span::default(),
+    })
+}
+
+/// Splices an element's content into its surroundings, unwrapping each
+/// element child to its own children. A stray text child has nowhere to
+/// splice to and is dropped.
+struct SpliceRule;
+
+impl Rule<Blob> for SpliceRule {
+    fn matches(&self, name: &[u8]) -> bool {
+        name == b"+"
+    }
+    fn apply(&self, elem: Elem<Blob>, rules: &Ruleset<Blob>) -> Vec<Node<Blob>> {
+        let grandchildren = elem.children.into_iter().flat_map(|child| match child {
+            Node::Elem(e) => e.children,
+            Node::Text(..) => Vec::new(),
+        }).collect();
+        transform(grandchildren, rules)
+    }
+}
+
+/// Writes an element's name and delimiters back out as written, around
+/// its rewritten children, for an element whose name is empty or ends in
+/// `=`.
+struct VerbatimRule;
+
+impl Rule<Blob> for VerbatimRule {
+    fn matches(&self, name: &[u8]) -> bool {
+        name.is_empty() || name.last() == Some(&b'=')
+    }
+    fn apply(&self, elem: Elem<Blob>, rules: &Ruleset<Blob>) -> Vec<Node<Blob>> {
+        let mut v = vec![
+            literal_bytes(elem.name.as_bytes().to_vec()),
+            literal_bytes(elem.delim.open().as_bytes().to_vec()),
+        ];
+        v.extend(transform(elem.children, rules));
+        v.push(literal_bytes(elem.delim.close().as_bytes().to_vec()));
+        v
+    }
+}
+
+/// Unwraps a literal (`\`-escaped) element into its plain content, which
+/// carries no output meaning of its own.
+struct LiteralRule;
+
+impl Rule<Blob> for LiteralRule {
+    fn matches(&self, name: &[u8]) -> bool {
+        is_literal(name)
+    }
+    fn apply(&self, elem: Elem<Blob>, rules: &Ruleset<Blob>) -> Vec<Node<Blob>> {
+        transform(elem.children, rules)
+    }
+}
+
+/// The default: wraps an element in an HTML tag of the same name.
+struct TagRule;
+
+impl Rule<Blob> for TagRule {
+    fn matches(&self, _name: &[u8]) -> bool {
+        true
+    }
+    fn apply(&self, elem: Elem<Blob>, rules: &Ruleset<Blob>) -> Vec<Node<Blob>> {
+        let name = elem.name.as_bytes();
+        let mut v = vec![literal_bytes([&b"<"[..], name, &b">"[..]].concat())];
+        v.extend(transform(elem.children, rules));
+        v.push(literal_bytes([&b"</"[..], name, &b">"[..]].concat()));
+        v
+    }
+}
+
+/// The `Ruleset` used by `write_html`: splice and verbatim rules take
+/// priority, literal elements are unwrapped to their content, and anything else
+/// falls through to `TagRule`.
+pub fn ruleset() -> Ruleset<Blob> {
+    let mut rules = Ruleset::new();
+    rules.push(SpliceRule);
+    rules.push(VerbatimRule);
+    rules.push(LiteralRule);
+    rules.push(TagRule);
+    rules
+}
+
+fn escape_to<W: io::Write>(f: &mut W, bytes: &[u8]) -> io::Result<()> {
+    for &b in bytes {
+        match b {
+            b'<' => f.write_all(b"&lt;")?,
+            b'>' => f.write_all(b"&gt;")?,
+            b'&' => f.write_all(b"&amp;")?,
+            _ => f.write_all(&[b])?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes `node`'s text byte-for-byte, with no escaping — used under a
+/// literal element, whose content is already in its final form. After
+/// `transform` the only literal elements left are the markup the `Rule`s
+/// above synthesized; `LiteralRule` has already unwrapped the user's own.
+fn write_raw<W: io::Write>(node: &Node<Blob>, f: &mut W) -> io::Result<()> {
+    match node {
+        &Node::Text(ref t, _) => f.write_all(t.as_bytes()),
+        &Node::Elem(ref e) => {
+            for child in &e.children {
+                write_raw(child, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn write_node<W: io::Write>(node: &Node<Blob>, f: &mut W) -> io::Result<()> {
+    match node {
+        &Node::Text(ref t, _) => escape_to(f, t.as_bytes()),
+        &Node::Elem(ref e) if is_literal(e.name.as_bytes()) => {
+            for child in &e.children {
+                write_raw(child, f)?;
+            }
+            Ok(())
+        }
+        &Node::Elem(ref e) => {
+            // Only reachable if `ruleset` didn't cover some name — the
+            // shipped ruleset always ends in `TagRule`, which matches
+            // anything, so every element is rewritten before we get
+            // here. Fall back to recursing past it rather than losing
+            // its children.
+            for child in &e.children {
+                write_node(child, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs `nodes` through `ruleset()` and writes the result as HTML,
+/// escaping `<`, `>`, and `&` in any text the ruleset didn't itself
+/// produce as markup.
+pub fn write_html<W: io::Write>(f: &mut W, nodes: Vec<Node<Blob>>) -> io::Result<()> {
+    let nodes = transform(nodes, &ruleset());
+    for node in &nodes {
+        write_node(node, f)?;
+    }
+    Ok(())
+}
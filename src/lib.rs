@@ -0,0 +1,4 @@
+pub mod parser;
+pub mod debug;
+pub mod transform;
+pub mod html;
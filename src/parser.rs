@@ -3,7 +3,6 @@ use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::io::Read;
 use std::sync::Arc;
-use regex::bytes::Regex;
 use debug::debug_utf8;
 
 pub mod delimiter {
@@ -97,54 +96,164 @@ fn is_word_char(c: u8) -> bool {
     !(is_ascii_space(c) || c == DIVIDER || c == ESCAPER)
 }
 
-/// If `name` is empty, then the location is considered unknown.
-#[derive(Clone, Debug)]
-pub struct Loc {
-    /// Name of the file.
-    pub name: Arc<String>,
+/// Identifies a file registered in a `SourceMap`, cheaper to copy than
+/// the file name it stands in for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct FileId(Option<usize>);
 
-    /// Zero-based line number.
-    pub row: usize,
+/// A position within a file known to some `SourceMap`, as a byte offset
+/// rather than a row/column pair.
+///
+/// If `file` is unknown, the location is considered unknown.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct Loc {
+    pub file: FileId,
 
-    /// Zero-based column number.
-    pub col: usize,
+    /// Zero-based byte offset into the file's contents.
+    pub offset: usize,
 }
 
 impl Loc {
     pub fn update<I: IntoIterator<Item=u8>>(&mut self, bytes: I) {
-        for c in bytes {
-            self.col += 1;
+        self.offset += bytes.into_iter().count();
+    }
+}
+
+/// A range within a file, from `start` up to (but not including) `end`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Default)]
+pub struct Span {
+    pub start: Loc,
+    pub end: Loc,
+}
+
+struct SourceFile {
+    name: Arc<String>,
+
+    /// Absent for a file registered by `add_file_name` alone; see there.
+    contents: Option<Arc<[u8]>>,
+}
+
+/// Owns the input buffers for every file parsed through it, and hands out
+/// `Loc`/`Span` values good for an interned `FileId` plus byte offsets.
+/// Row/column is only recovered by rescanning a file's contents, and only
+/// when a location is actually displayed.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: Vec::new() }
+    }
+
+    /// Registers a file's contents under `name`, returning the `FileId`
+    /// subsequent `Loc`s will refer to it by.
+    pub fn add_file(&mut self, name: &str, contents: Vec<u8>) -> FileId {
+        let id = FileId(Some(self.files.len()));
+        self.files.push(SourceFile {
+            name: Arc::new(name.to_string()),
+            contents: Some(Arc::from(contents)),
+        });
+        id
+    }
+
+    /// Registers `name` without any contents, for a file parsed
+    /// incrementally from a `Read`; its locations show a byte offset
+    /// rather than a row/col.
+    pub fn add_file_name(&mut self, name: &str) -> FileId {
+        let id = FileId(Some(self.files.len()));
+        self.files.push(SourceFile {
+            name: Arc::new(name.to_string()),
+            contents: None,
+        });
+        id
+    }
+
+    fn file(&self, id: FileId) -> Option<&SourceFile> {
+        id.0.map(|i| &self.files[i])
+    }
+
+    pub fn contents(&self, id: FileId) -> &[u8] {
+        match self.file(id).and_then(|f| f.contents.as_ref()) {
+            Some(c) => c,
+            None => &[][..],
+        }
+    }
+
+    fn row_col(&self, loc: Loc) -> Option<(usize, usize)> {
+        let f = self.file(loc.file)?;
+        let contents = f.contents.as_ref()?;
+        let mut row = 0;
+        let mut col = 0;
+        for &c in &contents[..loc.offset.min(contents.len())] {
             if c == b'\n' {
-                self.col = 0;
-                self.row += 1;
+                row += 1;
+                col = 0;
+            } else {
+                col += 1;
             }
         }
+        Some((row, col))
+    }
+
+    pub fn display_loc(&self, loc: Loc) -> LocDisplay {
+        LocDisplay { map: self, loc: loc }
+    }
+
+    pub fn display_span(&self, span: Span) -> SpanDisplay {
+        SpanDisplay { map: self, span: span }
     }
+
+    /// Registers `contents` under `path` and parses it, producing a tree
+    /// whose spans point back into the buffer this map now owns.
+    pub fn parse(&mut self, path: &str, contents: Vec<u8>) -> (Vec<Node>, Vec<String>) {
+        let file = self.add_file(path, contents);
+        let loc = Loc { file: file, offset: 0 };
+        let input = self.contents(file);
+        Node::parse_tokens(self, Lexer::new(input, loc))
+    }
+
+    /// Parses `reader` incrementally, buffering only the bytes since the
+    /// last tag rather than the whole input — unlike `parse`, which needs
+    /// `contents` up front. Input with no tags is still buffered whole.
+    pub fn parse_reader<R: io::Read>(&mut self, path: &str, reader: R)
+                                      -> io::Result<(Vec<Node>, Vec<String>)> {
+        let file = self.add_file_name(path);
+        let loc = Loc { file: file, offset: 0 };
+        parse_streamed(self, loc, reader)
+    }
+}
+
+pub struct LocDisplay<'a> {
+    map: &'a SourceMap,
+    loc: Loc,
 }
 
-impl<'a> From<&'a str> for Loc {
-    fn from(name: &'a str) -> Self {
-        Loc {
-            name: Arc::new(String::from(name)),
-            row: 0,
-            col: 0,
+impl<'a> fmt::Display for LocDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.map.file(self.loc.file) {
+            None => write!(f, "<unknown>"),
+            Some(file) => {
+                match self.map.row_col(self.loc) {
+                    Some((row, col)) => write!(f, "{}:{}:{}", file.name, row + 1, col + 1),
+                    None => write!(f, "{}:+{}", file.name, self.loc.offset),
+                }
+            }
         }
     }
 }
 
-impl Default for Loc {
-    fn default() -> Self {
-        Loc::from(<&str>::default())
-    }
+pub struct SpanDisplay<'a> {
+    map: &'a SourceMap,
+    span: Span,
 }
 
-impl fmt::Display for Loc {
+impl<'a> fmt::Display for SpanDisplay<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.name.is_empty() {
-            write!(f, "<unknown>")
-        } else {
-            write!(f, "{}:{}:{}", self.name, self.row + 1, self.col + 1)
-        }
+        write!(f, "{}-{}",
+               self.map.display_loc(self.span.start),
+               self.map.display_loc(self.span.end))
     }
 }
 
@@ -176,7 +285,7 @@ impl<'a> fmt::Debug for Token<'a> {
 struct Lexer<'a> {
     input: &'a [u8],
     loc: Loc,
-    queue: VecDeque<(Loc, Token<'a>)>,
+    queue: VecDeque<(Span, Token<'a>)>,
 }
 
 impl<'a> Lexer<'a> {
@@ -188,8 +297,8 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn push(&mut self, token: Token<'a>) {
-        self.queue.push_back((self.loc.clone(), token));
+    fn push(&mut self, span: Span, token: Token<'a>) {
+        self.queue.push_back((span, token));
     }
 
     fn refill(&mut self) {
@@ -200,25 +309,18 @@ impl<'a> Lexer<'a> {
             return;
         }
 
-        lazy_static! {
-            static ref RE: Regex = Regex::new(concat!(
-                r"(?s)",
-                r"^(.*?)(",
-                r"(:?\\[^ \t\\|()\[\]{}]*)?[\])}]",
-                r"|",
-                r"[\\|]?[^ \t\\|()\[\]{}]*[(\[{]",
-                r")",
-            )).unwrap();
-        }
-        match RE.captures(self.input) {
+        match scan_tag(self.input) {
             None => {                   // last chunk
-                self.push(Chunk(self.input));
+                let start = self.loc;
+                self.loc.update(self.input.iter().cloned());
+                let chunk = self.input;
                 self.input = b"";
+                self.push(Span { start: start, end: self.loc }, Chunk(chunk));
             }
-            Some(caps) => {
-                self.input = self.input.split_at(caps.get(0).unwrap().end()).1;
-                let chunk = caps.get(1).unwrap().as_bytes();
-                let tag = caps.get(2).unwrap().as_bytes();
+            Some((word_start, delim_pos)) => {
+                let (chunk, rest) = self.input.split_at(word_start);
+                let (tag, rest) = rest.split_at(delim_pos - word_start + 1);
+                self.input = rest;
 
                 let (delim, word) = tag.split_last().unwrap();
                 let delim = Delimiter::try_from(*delim).unwrap();
@@ -228,17 +330,99 @@ impl<'a> Lexer<'a> {
                     word
                 };
 
-                self.push(Chunk(chunk));
+                let chunk_start = self.loc;
                 self.loc.update(chunk.iter().cloned());
-                self.push(Tag(word, delim));
+                self.push(Span { start: chunk_start, end: self.loc }, Chunk(chunk));
+
+                let tag_start = self.loc;
                 self.loc.update(tag.iter().cloned());
+                self.push(Span { start: tag_start, end: self.loc }, Tag(word, delim));
             }
         }
     }
 }
 
+/// What, if anything, preceded the word run `scan_tag` is scanning. A
+/// bare word run can only lead into an opening delimiter (`foo)` is
+/// `foo` plus a stray close); a leading `\` also allows a closing one.
+#[derive(Clone, Copy)]
+enum Prefix {
+    None,
+    Backslash,
+    Pipe,
+}
+
+/// Looks at one more byte, advancing `i`/`attempt` in place, so
+/// `scan_tag` and `ReadScanner::next_pair` can share this state machine
+/// instead of each re-implementing it over their own buffer.
+fn scan_tag_step(attempt: &mut Option<(usize, Prefix)>, i: &mut usize, c: u8) -> Option<(usize, usize)> {
+    match *attempt {
+        None => {
+            if Delimiter::try_from(c).is_ok() {
+                Some((*i, *i))        // bare delimiter: empty name
+            } else if c == ESCAPER {
+                *attempt = Some((*i, Prefix::Backslash));
+                *i += 1;
+                None
+            } else if c == DIVIDER {
+                *attempt = Some((*i, Prefix::Pipe));
+                *i += 1;
+                None
+            } else if is_word_char(c) {
+                *attempt = Some((*i, Prefix::None));
+                *i += 1;
+                None
+            } else {
+                *i += 1;
+                None
+            }
+        }
+        Some((start, prefix)) => {
+            if let Ok(d) = Delimiter::try_from(c) {
+                let matches = match (prefix, d.0) {
+                    (Prefix::Backslash, _) => true,
+                    (Prefix::Pipe, Open) | (Prefix::None, Open) => true,
+                    (Prefix::Pipe, Close) | (Prefix::None, Close) => false,
+                };
+                if matches {
+                    Some((start, *i))
+                } else {
+                    // not a valid tag after all — the run so far is
+                    // just chunk text; re-examine `c` (the delimiter)
+                    // fresh, since on its own it's still a bare tag
+                    *attempt = None;
+                    None
+                }
+            } else if is_word_char(c) {
+                *i += 1;
+                None
+            } else {
+                // whitespace, or another `\`/`|` — breaks the run
+                *attempt = None;
+                None
+            }
+        }
+    }
+}
+
+/// Scans `input` for the next tag's byte range, or `None` if it's all
+/// one final chunk. A single forward pass, so this stays linear.
+fn scan_tag(input: &[u8]) -> Option<(usize, usize)> {
+    let mut i = 0;
+    let mut attempt = None;
+    loop {
+        if i >= input.len() {
+            return None;
+        }
+        let c = input[i];
+        if let found @ Some(_) = scan_tag_step(&mut attempt, &mut i, c) {
+            return found;
+        }
+    }
+}
+
 impl<'a> Iterator for Lexer<'a> {
-    type Item = (Loc, Token<'a>);
+    type Item = (Span, Token<'a>);
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.queue.is_empty() {
@@ -248,6 +432,120 @@ impl<'a> Iterator for Lexer<'a> {
     }
 }
 
+/// How many bytes `ReadScanner` asks its underlying `Read` for at a time.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Drives the same tag grammar as `Lexer`, but pulls bytes from a `Read`
+/// in chunks instead of slicing an in-memory buffer.
+struct ReadScanner<R> {
+    reader: R,
+    window: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: io::Read> ReadScanner<R> {
+    fn new(reader: R) -> Self {
+        ReadScanner { reader: reader, window: Vec::new(), pos: 0, eof: false }
+    }
+
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut buf = [0u8; READ_CHUNK_SIZE];
+        let n = self.reader.read(&mut buf)?;
+        if n == 0 {
+            self.eof = true;
+            Ok(false)
+        } else {
+            self.window.extend_from_slice(&buf[..n]);
+            Ok(true)
+        }
+    }
+
+    /// Returns the next chunk, and the tag that ended it (or `None` if
+    /// the reader ran dry before a tag showed up, in which case the
+    /// chunk is everything that's left). The tag's `usize` is its full
+    /// raw byte length (prefix included), for advancing a `Loc` by
+    /// exactly as much as `word`/`delim` together account for.
+    fn next_pair(&mut self) -> io::Result<Option<(Vec<u8>, Option<(Vec<u8>, Delimiter, usize)>)>> {
+        self.window.drain(0..self.pos);
+        self.pos = 0;
+        if self.window.is_empty() && !self.fill()? {
+            return Ok(None);
+        }
+
+        let mut i = 0;
+        let mut attempt = None;
+        let tag_end = loop {
+            if i >= self.window.len() {
+                if !self.fill()? {
+                    break None;
+                }
+                continue;
+            }
+            let c = self.window[i];
+            if let found @ Some(_) = scan_tag_step(&mut attempt, &mut i, c) {
+                break found;
+            }
+        };
+
+        match tag_end {
+            None => {
+                let chunk = mem::replace(&mut self.window, Vec::new());
+                self.pos = 0;
+                Ok(Some((chunk, None)))
+            }
+            Some((word_start, delim_pos)) => {
+                let chunk = self.window[..word_start].to_vec();
+                let tag = &self.window[word_start..=delim_pos];
+                let tag_len = tag.len();
+                let (&delim_byte, word) = tag.split_last().unwrap();
+                let delim = Delimiter::try_from(delim_byte).unwrap();
+                let word = if let Some((&b'|', word)) = word.split_first() {
+                    word
+                } else {
+                    word
+                }.to_vec();
+                self.pos = delim_pos + 1;
+                Ok(Some((chunk, Some((word, delim, tag_len)))))
+            }
+        }
+    }
+}
+
+/// Parses `reader` incrementally, feeding chunks and tags from
+/// `ReadScanner` into the same `step` stack machine as `Node::parse_tokens`.
+fn parse_streamed<R: io::Read>(map: &SourceMap, mut loc: Loc, reader: R)
+                                -> io::Result<(Vec<Node>, Vec<String>)> {
+    let mut scanner = ReadScanner::new(reader);
+    let mut errs = Vec::new();
+    let mut stack = Vec::new();
+    let mut top = Elem {
+        name: Blob::default(),
+        delim: Delim::Parenthesis,
+        children: Vec::new(),
+        span: Default::default(),
+    };
+    while let Some((chunk, tag)) = scanner.next_pair()? {
+        let start = loc;
+        loc.update(chunk.iter().cloned());
+        step(map, &mut stack, &mut top, &mut errs,
+             Span { start: start, end: loc }, Token::Chunk(&chunk[..]));
+        let (word, delim, tag_len) = match tag {
+            Some(t) => t,
+            None => break,
+        };
+        let start = loc;
+        loc.offset += tag_len;
+        step(map, &mut stack, &mut top, &mut errs,
+             Span { start: start, end: loc }, Token::Tag(&word[..], delim));
+    }
+    let nodes = finish_tokens(map, stack, top, &mut errs);
+    Ok((nodes, errs))
+}
+
 /// Stores binary data.
 #[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Blob(Arc<Box<[u8]>>);
@@ -322,32 +620,37 @@ impl<'a, 'b> std::ops::Add<&'b Blob> for &'a Blob{
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Elem {
-    pub name: Blob,
+/// An element `name(children)`, `name[children]`, or `name{children}`.
+/// Generic over its byte storage `S` — `Blob` for an owned, shareable
+/// tree, or `&[u8]` to borrow straight from the parsed input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Elem<S = Blob> {
+    pub name: S,
     pub delim: Delim,
-    pub children: Vec<Node>,
-    pub loc: Loc,
+    pub children: Vec<Node<S>>,
+    pub span: Span,
 }
 
-fn escape_delim<'a>(delim: Delimiter) -> Node {
+fn escape_delim<'a, S>(delim: Delimiter) -> Node<S>
+    where S: From<&'a [u8]>
+{
     Node::Elem(Elem {
-        name: Blob::from(&[ESCAPER] as &[u8]),
+        name: S::from(&[ESCAPER]),
         delim: Delim::Parenthesis,
         children: vec![Node::from(delim.as_bytes())],
-        loc: Default::default(),
+        span: Default::default(),
     })
 }
 
-impl Elem {
-    /// Melt the node into a mix of text nodes and child nodes.
-    /// The closing delimiter is not included.
-    fn into_text_nodes(self) -> impl Iterator<Item=Node> {
-        let delim = self.delim.open();
-        iter::once(Node::Text(self.name))
-            .chain(iter::once(escape_delim(delim)))
-            .chain(self.children.into_iter())
-    }
+/// Melt `elem` into a mix of text nodes and child nodes. The closing
+/// delimiter is not included.
+fn into_text_nodes<'a, S>(elem: Elem<S>) -> impl Iterator<Item=Node<S>>
+    where S: From<&'a [u8]>
+{
+    let delim = elem.delim.open();
+    iter::once(Node::Text(elem.name, elem.span))
+        .chain(iter::once(escape_delim(delim)))
+        .chain(elem.children.into_iter())
 }
 
 pub trait WriteTo {
@@ -373,7 +676,7 @@ impl<'a> WriteTo for [u8] {
 
 pub enum NodeWriteState { Clean, Sticky }
 
-impl<'a> WriteTo for [Node] {
+impl<'a, S: AsRef<[u8]>> WriteTo for [Node<S>] {
     type State = NodeWriteState;
     fn write_to<W>(&self, f: &mut W, s: &mut Self::State)
                    -> io::Result<()> where W: io::Write {
@@ -384,14 +687,14 @@ impl<'a> WriteTo for [Node] {
     }
 }
 
-impl WriteTo for Node {
+impl<S: AsRef<[u8]>> WriteTo for Node<S> {
     type State = NodeWriteState;
     fn write_to<W>(&self, f: &mut W, s: &mut Self::State)
                    -> io::Result<()> where W: io::Write {
         match self {
-            &Node::Text(ref t) => {
-                t.write_to(f, &mut ())?;
-                if is_word_char(*t.last().unwrap_or(&b' ')) {
+            &Node::Text(ref t, _) => {
+                t.as_ref().write_to(f, &mut ())?;
+                if is_word_char(*t.as_ref().last().unwrap_or(&b' ')) {
                     *s = NodeWriteState::Sticky;
                 } else {
                     *s = NodeWriteState::Clean;
@@ -401,11 +704,11 @@ impl WriteTo for Node {
                 if let &mut NodeWriteState::Sticky = s {
                     [DIVIDER].write_to(f, &mut ())?;
                 }
-                elem.name.write_to(f, &mut ())?;
+                elem.name.as_ref().write_to(f, &mut ())?;
                 elem.delim.open().as_bytes().write_to(f, &mut ())?;
                 elem.children.write_to(f, s)?;
-                if is_literal(&elem.name) {
-                    elem.name.write_to(f, &mut ())?;
+                if is_literal(elem.name.as_ref()) {
+                    elem.name.as_ref().write_to(f, &mut ())?;
                 }
                 elem.delim.close().as_bytes().write_to(f, &mut ())?;
                 *s = NodeWriteState::Clean;
@@ -415,21 +718,16 @@ impl WriteTo for Node {
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum Node {
-    Text(Blob),
-    Elem(Elem),
+/// A node of the tree: either a run of text, or an element; see `Elem`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node<S = Blob> {
+    Text(S, Span),
+    Elem(Elem<S>),
 }
 
-impl<'a> From<&'a [u8]> for Node {
+impl<'a, S: From<&'a [u8]>> From<&'a [u8]> for Node<S> {
     fn from(s: &'a [u8]) -> Self {
-        Node::Text(Blob::from(s))
-    }
-}
-
-impl<'a> From<&'a str> for Node {
-    fn from(s: &'a str) -> Self {
-        Node::Text(Blob::from(s))
+        Node::Text(S::from(s), Default::default())
     }
 }
 
@@ -440,100 +738,339 @@ pub fn is_literal(name: &[u8]) -> bool {
     }
 }
 
-impl Node {
-    pub fn parse(s: &[u8], path: &str) ->(Vec<Self>, Vec<String>) {
-        Node::parse_tokens(Lexer::new(&s, Loc::from(path)))
+/// Promotes a borrowed tree into an owned one backed by `Blob`, for when
+/// it must outlive the buffer it was borrowed from.
+pub trait IntoOwned {
+    type Owned;
+    fn into_owned(self) -> Self::Owned;
+}
+
+impl<'a> IntoOwned for Node<&'a [u8]> {
+    type Owned = Node<Blob>;
+    fn into_owned(self) -> Node<Blob> {
+        match self {
+            Node::Text(t, span) => Node::Text(Blob::from(t), span),
+            Node::Elem(elem) => Node::Elem(elem.into_owned()),
+        }
     }
+}
 
-    fn parse_tokens<'a, I>(tokens: I) -> (Vec<Self>, Vec<String>)
-        where I: Iterator<Item=(Loc, Token<'a>)>
+impl<'a> IntoOwned for Elem<&'a [u8]> {
+    type Owned = Elem<Blob>;
+    fn into_owned(self) -> Elem<Blob> {
+        Elem {
+            name: Blob::from(self.name),
+            delim: self.delim,
+            children: self.children.into_iter().map(IntoOwned::into_owned).collect(),
+            span: self.span,
+        }
+    }
+}
+
+/// Folds a single lexer `token` into the in-progress parse state. Shared
+/// by `Node::parse_tokens` and `parse_streamed` so the stack machine only
+/// exists once.
+fn step<'b, S>(map: &SourceMap, stack: &mut Vec<Elem<S>>, top: &mut Elem<S>,
+               errs: &mut Vec<String>, span: Span, token: Token<'b>)
+    where S: From<&'b [u8]> + AsRef<[u8]>
+{
+    let esc = is_literal(top.name.as_ref());
+    match token {
+        Token::Chunk(s) => {
+            top.children.push(Node::Text(S::from(s), span));
+        }
+        Token::Tag(word, delim) => match delim {
+            _ if esc && top.name.as_ref() != word => {
+                top.children.push(Node::Text(S::from(word), span));
+                top.children.push(Node::Text(S::from(delim.as_bytes()), span));
+            }
+            Delimiter(Open, dtype) => {
+                let prev = mem::replace(top, Elem {
+                    name: S::from(word),
+                    delim: dtype,
+                    children: Vec::new(),
+                    span: span,
+                });
+                stack.push(prev);
+            }
+            Delimiter(Close, dtype) => {
+                if !esc {
+                    top.children.push(Node::Text(S::from(word), span));
+                }
+                if top.delim != dtype {
+                    let d = Delimiter(Open, top.delim);
+                    errs.push(format!(
+                        "{}: ‘{}’ doesn’t close ‘{}{}’ at {}",
+                        map.display_span(span),
+                        String::from_utf8_lossy(delim.as_bytes()),
+                        debug_utf8(top.name.as_ref()),
+                        String::from_utf8_lossy(d.as_bytes()),
+                        map.display_span(top.span)));
+                    top.children.push(escape_delim(d));
+                } else {
+                    match stack.pop() {
+                        None => {
+                            // we're at root level (which is never an
+                            // escaping context), so there's nothing to
+                            // close
+                            let d = delim.as_bytes();
+                            errs.push(format!(
+                                "{}: ‘{}’ doesn’t close anything",
+                                map.display_span(span), String::from_utf8_lossy(d)));
+                            top.children.push(Node::Text(S::from(d), span));
+                        }
+                        Some(new_top) => {
+                            let mut elem_span = top.span;
+                            elem_span.end = span.end;
+                            let closed = mem::replace(top, new_top);
+                            top.children.push(Node::Elem(Elem {
+                                name: closed.name,
+                                delim: closed.delim,
+                                children: closed.children,
+                                span: elem_span,
+                            }));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drains the still-open `top`/`stack` once the token stream runs out,
+/// reporting the innermost unclosed element as an error and flattening
+/// every unclosed element back into text.
+fn finish_tokens<'a, S>(map: &SourceMap, stack: Vec<Elem<S>>, mut top: Elem<S>, errs: &mut Vec<String>)
+                        -> Vec<Node<S>>
+    where S: From<&'a [u8]> + AsRef<[u8]>
+{
+    let mut stack = stack;
+    let mut nodes = mem::replace(match stack.first_mut() {
+        Some(root) => {
+            let d = Delimiter(Open, top.delim).as_bytes();
+            errs.push(format!(
+                "{}: ‘{}{}’ was never closed",
+                map.display_span(top.span),
+                String::from_utf8_lossy(top.name.as_ref()),
+                String::from_utf8_lossy(&d)));
+            &mut root.children
+        }
+        None => &mut top.children,
+    }, Vec::new());
+    // flatten the unclosed elements into text
+    for elem in stack.into_iter().chain(iter::once(top)).skip(1) {
+        nodes.extend(into_text_nodes(elem));
+    }
+    nodes
+}
+
+impl<S> Node<S> {
+    fn parse_tokens<'a, I>(map: &SourceMap, tokens: I) -> (Vec<Self>, Vec<String>)
+        where I: Iterator<Item=(Span, Token<'a>)>,
+              S: From<&'a [u8]> + Default + AsRef<[u8]>
     {
         let mut errs = Vec::new();
         let mut stack = Vec::new();
         let mut top = Elem {
-            name: Blob::default(),
+            name: S::default(),
             delim: Delim::Parenthesis,
             children: Vec::new(),
-            loc: Default::default(),
+            span: Default::default(),
         };
-        for token in tokens {
-            let esc = is_literal(&top.name);
-            match token {
-                (_, Token::Chunk(s)) => {
-                    top.children.push(Node::from(s));
+        for (span, token) in tokens {
+            step(map, &mut stack, &mut top, &mut errs, span, token);
+        }
+        let nodes = finish_tokens(map, stack, top, &mut errs);
+        (nodes, errs)
+    }
+}
+
+fn write_varint<W: io::Write>(f: &mut W, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return f.write_all(&[byte]);
+        }
+        f.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Selects the canonical binary encoding, rather than the text form
+/// `Node` itself writes via `WriteTo`. A `Node::Text` is `T`, a varint
+/// byte length, then the bytes; a `Node::Elem` is `E`, its opening
+/// delimiter byte, a varint name length, the name, a varint child count,
+/// then each child encoded the same way. Varints are base-128, low
+/// group first.
+pub struct BinaryForm<'a, S: 'a = Blob>(pub &'a Node<S>);
+
+impl<'a, S: AsRef<[u8]>> WriteTo for BinaryForm<'a, S> {
+    type State = ();
+    fn write_to<W>(&self, f: &mut W, _: &mut ()) -> io::Result<()> where W: io::Write {
+        match self.0 {
+            &Node::Text(ref t, _) => {
+                f.write_all(b"T")?;
+                let t = t.as_ref();
+                write_varint(f, t.len() as u64)?;
+                f.write_all(t)
+            }
+            &Node::Elem(ref elem) => {
+                f.write_all(b"E")?;
+                f.write_all(elem.delim.open().as_bytes())?;
+                let name = elem.name.as_ref();
+                write_varint(f, name.len() as u64)?;
+                f.write_all(name)?;
+                write_varint(f, elem.children.len() as u64)?;
+                for child in &elem.children {
+                    BinaryForm(child).write_to(f, &mut ())?;
                 }
-                (loc, Token::Tag(word, delim)) => match delim {
-                    _ if esc && top.name.as_bytes() != word => {
-                        top.children.push(Node::from(word));
-                        top.children.push(Node::from(delim.as_bytes()));
-                    }
-                    Delimiter(Open, dtype) => {
-                        stack.push(top);
-                        top = Elem {
-                            name: Blob::from(word),
-                            delim: dtype,
-                            children: Vec::new(),
-                            loc: loc,
-                        };
-                    }
-                    Delimiter(Close, dtype) => {
-                        if !esc {
-                            top.children.push(Node::from(word));
-                        }
-                        if top.delim != dtype {
-                            let d = Delimiter(Open, top.delim);
-                            errs.push(format!(
-                                "{}: ‘{}’ doesn’t close ‘{}{}’ at {}",
-                                loc,
-                                String::from_utf8_lossy(delim.as_bytes()),
-                                debug_utf8(&top.name),
-                                String::from_utf8_lossy(d.as_bytes()),
-                                top.loc));
-                            top.children.push(escape_delim(d));
-                        } else {
-                            match stack.pop() {
-                                None => {
-                                    // we're at root level (which is never
-                                    // an escaping context), so there's
-                                    // nothing to close
-                                    let d = delim.as_bytes();
-                                    errs.push(format!(
-                                        "{}: ‘{}’ doesn’t close anything",
-                                        loc, String::from_utf8_lossy(d)));
-                                    top.children.push(Node::from(d));
-                                }
-                                Some(mut new_top) => {
-                                    new_top.children.push(Node::Elem(Elem {
-                                        name: top.name,
-                                        delim: top.delim,
-                                        children: top.children,
-                                        loc: top.loc,
-                                    }));
-                                    top = new_top;
-                                }
-                            }
-                        }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Encodes `nodes` in the canonical binary form, such that
+/// `Node::decode(&encode(nodes))` reproduces `nodes` exactly.
+pub fn encode<S: AsRef<[u8]>>(nodes: &[Node<S>]) -> Vec<u8> {
+    let mut v = Vec::new();
+    for node in nodes {
+        BinaryForm(node).write_to(&mut v, &mut ()).unwrap();
+    }
+    v
+}
+
+#[derive(Clone, Debug)]
+pub enum DecodeError {
+    /// The input ended in the middle of a value.
+    UnexpectedEof,
+    /// A tag byte was neither `T` nor `E`.
+    UnknownTag(u8),
+    /// An `Elem`'s delimiter byte wasn't one of `(`, `[`, `{`.
+    InvalidDelim(u8),
+    /// There were bytes left over after decoding the top-level values.
+    TrailingBytes,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &DecodeError::UnexpectedEof => write!(f, "unexpected end of input"),
+            &DecodeError::UnknownTag(b) => write!(f, "unknown tag byte {:#04x}", b),
+            &DecodeError::InvalidDelim(b) => write!(f, "invalid delimiter byte {:#04x}", b),
+            &DecodeError::TrailingBytes => write!(f, "trailing bytes after last value"),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        let (&b, rest) = self.input.split_first().ok_or(DecodeError::UnexpectedEof)?;
+        self.input = rest;
+        Ok(b)
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.input.len() < n {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.input.split_at(n);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            result |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_elem_header(&mut self) -> Result<(Blob, Delim, u64), DecodeError> {
+        let delim_byte = self.read_u8()?;
+        let delim = Delimiter::try_from(delim_byte).ok()
+            .filter(|d| d.0 == Open)
+            .ok_or(DecodeError::InvalidDelim(delim_byte))?;
+        let name_len = self.read_varint()? as usize;
+        let name = Blob::from(self.read_bytes(name_len)?);
+        let child_count = self.read_varint()?;
+        Ok((name, delim.1, child_count))
+    }
+
+    fn read_text(&mut self) -> Result<Node, DecodeError> {
+        let len = self.read_varint()? as usize;
+        let bytes = self.read_bytes(len)?;
+        Ok(Node::Text(Blob::from(bytes), Span::default()))
+    }
+}
+
+/// An `Elem` still waiting on `remaining` more child values to decode.
+struct PendingElem {
+    name: Blob,
+    delim: Delim,
+    children: Vec<Node>,
+    remaining: u64,
+}
+
+impl Node {
+    /// Decodes the canonical binary form produced by `encode`, walking an
+    /// explicit stack of `PendingElem`s rather than recursing per `Elem`.
+    /// Spans aren't part of the encoding, so every decoded node gets
+    /// `Span::default()` rather than its original location. This only
+    /// bounds the decoding step itself by the heap — the `Vec<Node>` it
+    /// returns still drops recursively, so a caller that builds one from
+    /// adversarial input and then drops it can still overflow the stack.
+    pub fn decode(input: &[u8]) -> Result<Vec<Node>, DecodeError> {
+        let mut dec = Decoder { input: input };
+        let mut stack: Vec<PendingElem> = Vec::new();
+        let mut nodes = Vec::new();
+        loop {
+            if let Some(pending) = stack.last() {
+                if pending.remaining == 0 {
+                    let pending = stack.pop().unwrap();
+                    let elem = Node::Elem(Elem {
+                        name: pending.name,
+                        delim: pending.delim,
+                        children: pending.children,
+                        span: Span::default(),
+                    });
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(elem),
+                        None => nodes.push(elem),
                     }
+                    continue;
                 }
+            } else if dec.input.is_empty() {
+                break;
             }
-        }
-        let mut nodes = mem::replace(match stack.first_mut() {
-            Some(root) => {
-                let d = Delimiter(Open, top.delim).as_bytes();
-                errs.push(format!(
-                    "{}: ‘{}{}’ was never closed",
-                    top.loc,
-                    String::from_utf8_lossy(&top.name),
-                    String::from_utf8_lossy(&d)));
-                &mut root.children
+            if let Some(pending) = stack.last_mut() {
+                pending.remaining -= 1;
+            }
+            let node = match dec.read_u8()? {
+                b'T' => dec.read_text()?,
+                b'E' => {
+                    let (name, delim, remaining) = dec.read_elem_header()?;
+                    stack.push(PendingElem { name: name, delim: delim, children: Vec::new(), remaining: remaining });
+                    continue;
+                }
+                tag => return Err(DecodeError::UnknownTag(tag)),
+            };
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => nodes.push(node),
             }
-            None => &mut top.children,
-        }, Vec::new());
-        // flatten the unclosed elements into text
-        for elem in stack.into_iter().chain(iter::once(top)).skip(1) {
-            nodes.extend(elem.into_text_nodes());
         }
-        (nodes, errs)
+        Ok(nodes)
     }
 }
 
@@ -544,6 +1081,126 @@ pub fn load_file(path: &str) -> Vec<u8> {
     s
 }
 
-pub fn render_doc(nodes: &[Node]) -> Vec<u8> {
+/// Parses `input` without copying its bytes: every `Node`/`Elem` in the
+/// result slices directly into `input`. Call `IntoOwned::into_owned` if
+/// the tree needs to outlive `input`.
+pub fn parse_borrowed<'a>(input: &'a [u8]) -> (Vec<Node<&'a [u8]>>, Vec<String>) {
+    let map = SourceMap::new();
+    Node::parse_tokens(&map, Lexer::new(input, Loc::default()))
+}
+
+pub fn render_doc<S: AsRef<[u8]>>(nodes: &[Node<S>]) -> Vec<u8> {
     write_to_vec(nodes, &mut NodeWriteState::Clean)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `decode` always gives back `Span::default()` (spans aren't part of
+    /// the binary form), so a round trip can only match the original tree
+    /// up to spans — strip them from both sides before comparing.
+    fn clear_spans(nodes: Vec<Node>) -> Vec<Node> {
+        nodes.into_iter().map(|node| match node {
+            Node::Text(s, _) => Node::Text(s, Span::default()),
+            Node::Elem(e) => Node::Elem(Elem {
+                name: e.name,
+                delim: e.delim,
+                children: clear_spans(e.children),
+                span: Span::default(),
+            }),
+        }).collect()
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let (nodes, errs) = parse_borrowed(b"tag(child1(a) child2[b] child3{c})");
+        assert!(errs.is_empty());
+        let nodes: Vec<_> = nodes.into_iter().map(IntoOwned::into_owned).collect();
+        let decoded = Node::decode(&encode(&nodes)).unwrap();
+        assert_eq!(decoded, clear_spans(nodes));
+    }
+
+    #[test]
+    fn decode_does_not_overflow_on_deep_nesting() {
+        let depth = 200_000;
+        let mut bytes = Vec::new();
+        for i in 0..depth {
+            bytes.push(b'E');
+            bytes.push(b'(');
+            write_varint(&mut bytes, 0).unwrap(); // name length
+            write_varint(&mut bytes, if i + 1 < depth { 1 } else { 0 }).unwrap();
+        }
+        let decoded = Node::decode(&bytes).unwrap();
+        let mut n = &decoded[0];
+        for _ in 1..depth {
+            n = match n {
+                &Node::Elem(ref e) => &e.children[0],
+                _ => panic!("expected a nested Elem"),
+            };
+        }
+        // `decoded` is a 200,000-deep tree; let it through ordinary
+        // recursive `Drop` glue and the test binary would stack-overflow
+        // on the way out, which is exactly what this test exists to rule
+        // out for `decode` itself — leak it instead of dropping it.
+        mem::forget(decoded);
+    }
+
+    #[test]
+    fn scan_tag_finds_bare_delimiter_as_empty_name() {
+        assert_eq!(scan_tag(b")"), Some((0, 0)));
+    }
+
+    #[test]
+    fn scan_tag_ignores_backslash_not_followed_by_a_delimiter() {
+        assert_eq!(scan_tag(b"\\foo bar"), None);
+    }
+
+    #[test]
+    fn scan_tag_lets_backslash_close_what_a_bare_word_cant() {
+        // `foo` can't lead into a close delimiter on its own — the run is
+        // abandoned as plain text and the `)` is re-examined fresh, as a
+        // bare (empty-name) delimiter in its own right.
+        assert_eq!(scan_tag(b"foo)"), Some((3, 3)));
+        // a leading `\` does let the same word run close.
+        assert_eq!(scan_tag(b"\\foo)"), Some((0, 4)));
+    }
+
+    #[test]
+    fn parse_reader_matches_parse_borrowed_on_piped_tags() {
+        let input: &[u8] = b"pre|tag(x) more|text[y]";
+        let (borrowed, errs) = parse_borrowed(input);
+        assert!(errs.is_empty());
+        let borrowed: Vec<_> = borrowed.into_iter().map(IntoOwned::into_owned).collect();
+
+        let mut map = SourceMap::new();
+        let (streamed, errs) = map.parse_reader("<test>", input).unwrap();
+        assert!(errs.is_empty());
+
+        assert_eq!(clear_spans(streamed), clear_spans(borrowed));
+    }
+
+    #[test]
+    fn parse_reader_advances_loc_past_the_stripped_pipe_byte() {
+        // "pre|tag(x)": the pipe before "tag" is part of the tag's raw
+        // bytes but not of `word`, and used to be dropped from `loc`.
+        let input: &[u8] = b"pre|tag(x)";
+        let mut map = SourceMap::new();
+        let (nodes, errs) = map.parse_reader("<test>", input).unwrap();
+        assert!(errs.is_empty());
+        assert_eq!(nodes.len(), 2);
+        let elem = match &nodes[1] {
+            Node::Elem(elem) => elem,
+            other => panic!("expected a trailing Elem, got {:?}", other),
+        };
+        assert_eq!(elem.span.start.offset, 3);
+        assert_eq!(elem.span.end.offset, 10);
+        match &elem.children[0] {
+            Node::Text(_, span) => {
+                assert_eq!(span.start.offset, 8);
+                assert_eq!(span.end.offset, 9);
+            }
+            other => panic!("expected a text child, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! A small rewrite-rule mechanism for mapping a parsed tag tree onto some
+//! output tree one element name at a time, instead of hardcoding the
+//! mapping inside whatever eventually renders it. See `html` for the
+//! HTML target built on top of this.
+use parser::{Blob, Elem, Node};
+
+/// Rewrites an element whose name it recognizes into zero or more
+/// replacement nodes. `elem`'s children are handed over untransformed;
+/// `apply` is on the hook to call `transform` on whatever children it
+/// keeps; one that forgets leaves them unrewritten, silently. Rules like
+/// `SpliceRule` that need to see the pre-transform shape of a child
+/// (rather than just passing it through) are the reason this isn't done
+/// for the rule automatically.
+pub trait Rule<S = Blob> {
+    /// Whether this rule applies to an element named `name`.
+    fn matches(&self, name: &[u8]) -> bool;
+
+    /// Rewrites `elem`.
+    fn apply(&self, elem: Elem<S>, rules: &Ruleset<S>) -> Vec<Node<S>>;
+}
+
+/// A rule that fires on elements with one particular, exact name.
+pub struct NameRule<S = Blob> {
+    name: Blob,
+    apply: Box<Fn(Elem<S>, &Ruleset<S>) -> Vec<Node<S>>>,
+}
+
+impl<S> NameRule<S> {
+    pub fn new<F>(name: &[u8], apply: F) -> Self
+        where F: Fn(Elem<S>, &Ruleset<S>) -> Vec<Node<S>> + 'static
+    {
+        NameRule { name: Blob::from(name), apply: Box::new(apply) }
+    }
+}
+
+impl<S> Rule<S> for NameRule<S> {
+    fn matches(&self, name: &[u8]) -> bool {
+        self.name.as_bytes() == name
+    }
+    fn apply(&self, elem: Elem<S>, rules: &Ruleset<S>) -> Vec<Node<S>> {
+        (self.apply)(elem, rules)
+    }
+}
+
+/// An ordered list of `Rule`s. `transform` uses the first one whose
+/// `matches` accepts an element's name, so an early catch-all rule
+/// shadows everything registered after it.
+#[derive(Default)]
+pub struct Ruleset<S = Blob> {
+    rules: Vec<Box<Rule<S>>>,
+}
+
+impl<S> Ruleset<S> {
+    pub fn new() -> Self {
+        Ruleset { rules: Vec::new() }
+    }
+
+    pub fn push<R: Rule<S> + 'static>(&mut self, rule: R) {
+        self.rules.push(Box::new(rule));
+    }
+
+    fn rule_for(&self, name: &[u8]) -> Option<&Rule<S>> {
+        self.rules.iter().find(|r| r.matches(name)).map(|r| &**r)
+    }
+}
+
+/// Rewrites `nodes`. An element with a matching rule in `rules` is handed
+/// to that rule untransformed, children included — see `Rule::apply`. An
+/// element with no matching rule is left as-is, except `transform` still
+/// recurses into its children.
+pub fn transform<S: AsRef<[u8]>>(nodes: Vec<Node<S>>, rules: &Ruleset<S>) -> Vec<Node<S>> {
+    nodes.into_iter().flat_map(|node| transform_node(node, rules)).collect()
+}
+
+fn transform_node<S: AsRef<[u8]>>(node: Node<S>, rules: &Ruleset<S>) -> Vec<Node<S>> {
+    match node {
+        Node::Text(..) => vec![node],
+        Node::Elem(elem) => {
+            match rules.rule_for(elem.name.as_ref()) {
+                Some(rule) => rule.apply(elem, rules),
+                None => vec![Node::Elem(Elem { children: transform(elem.children, rules), ..elem })],
+            }
+        }
+    }
+}